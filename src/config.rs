@@ -0,0 +1,10 @@
+use strum_macros::EnumString;
+
+/// Physical orientation the macropad is mounted in, used to rotate the
+/// key/row layout when reading or writing the configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum Orientation {
+    Normal,
+    Invert,
+}
@@ -0,0 +1,2 @@
+/// Maximum number of simultaneous key presses a single report can encode.
+pub const MAX_KEY_PRESSES: usize = 17;
@@ -0,0 +1,444 @@
+//! Foreground-application-aware auto-flashing daemon.
+//!
+//! Watches which application is focused and re-flashes the macropad with the
+//! RON profile that matches it, so e.g. Firefox and GIMP can have different
+//! mappings without the user swapping files by hand.
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::mapping::{Macropad, Mapping};
+use crate::messages::Messages;
+
+/// Resolves the class/name of the currently focused application.
+///
+/// Implementations shell out to whatever the display server exposes, since
+/// that's the only thing guaranteed to work across window managers without
+/// pulling in a full windowing client library.
+pub trait ActiveWindowBackend {
+    fn active_window_class(&self) -> Result<String>;
+}
+
+/// X11 backend, reading `_NET_ACTIVE_WINDOW` / `WM_CLASS` via `xprop`.
+pub struct X11Backend;
+
+impl ActiveWindowBackend for X11Backend {
+    fn active_window_class(&self) -> Result<String> {
+        let root = Command::new("xprop")
+            .args(["-root", "_NET_ACTIVE_WINDOW"])
+            .output()
+            .context("failed running xprop -root")?;
+        let root = String::from_utf8_lossy(&root.stdout);
+        let window_id = root
+            .split_whitespace()
+            .last()
+            .context("could not parse active window id from xprop")?;
+
+        let class = Command::new("xprop")
+            .args(["-id", window_id, "WM_CLASS"])
+            .output()
+            .context("failed running xprop -id")?;
+        let class = String::from_utf8_lossy(&class.stdout);
+        parse_wm_class(&class)
+    }
+}
+
+fn parse_wm_class(xprop_output: &str) -> Result<String> {
+    // WM_CLASS(STRING) = "firefox", "Firefox" - the second, general-purpose
+    // name is the one profiles are keyed on. A well-formed line always
+    // splits into an odd number of '"'-delimited pieces (at least 5: text,
+    // firefox, text, Firefox, text); anything else means there was no
+    // quoted WM_CLASS to begin with (e.g. "no such atom on any window.").
+    let quoted: Vec<_> = xprop_output.split('"').collect();
+    if quoted.len() < 5 || quoted.len() % 2 == 0 {
+        return Err(anyhow!("could not parse WM_CLASS from xprop output"));
+    }
+    quoted
+        .get(quoted.len() - 2)
+        .map(|s| s.to_lowercase())
+        .filter(|s| !s.is_empty())
+        .context("could not parse WM_CLASS from xprop output")
+}
+
+/// Sway backend, reading the focused node's `app_id` via `swaymsg`.
+pub struct SwayBackend;
+
+impl ActiveWindowBackend for SwayBackend {
+    fn active_window_class(&self) -> Result<String> {
+        let tree = Command::new("swaymsg")
+            .args(["-t", "get_tree"])
+            .output()
+            .context("failed running swaymsg -t get_tree")?;
+        let tree: serde_json::Value =
+            serde_json::from_slice(&tree.stdout).context("failed parsing swaymsg output")?;
+        find_focused_app_id(&tree).context("no focused node in sway tree")
+    }
+}
+
+fn find_focused_app_id(node: &serde_json::Value) -> Option<String> {
+    if node.get("focused").and_then(|v| v.as_bool()) == Some(true) {
+        if let Some(app_id) = node.get("app_id").and_then(|v| v.as_str()) {
+            return Some(app_id.to_lowercase());
+        }
+    }
+    // tiled and floating children are kept in separate arrays; a focused
+    // floating window (a dialog, a floating terminal, ...) only ever shows
+    // up under "floating_nodes"
+    ["nodes", "floating_nodes"].iter().find_map(|key| {
+        node.get(*key)?
+            .as_array()?
+            .iter()
+            .find_map(find_focused_app_id)
+    })
+}
+
+/// Hyprland backend, reading the active window's class via `hyprctl`.
+pub struct HyprlandBackend;
+
+impl ActiveWindowBackend for HyprlandBackend {
+    fn active_window_class(&self) -> Result<String> {
+        let window = Command::new("hyprctl")
+            .args(["activewindow", "-j"])
+            .output()
+            .context("failed running hyprctl activewindow -j")?;
+        let window: serde_json::Value =
+            serde_json::from_slice(&window.stdout).context("failed parsing hyprctl output")?;
+        window
+            .get("class")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_lowercase())
+            .context("could not parse class from hyprctl output")
+    }
+}
+
+/// Sends a built report to the macropad. Implemented by whatever owns the
+/// device handle (e.g. a hidapi wrapper) - `Messages` only builds reports, it
+/// never talks to hardware.
+pub trait FlashTarget {
+    fn send(&mut self, report: &[u8]) -> Result<()>;
+}
+
+/// Watches the focused application and flashes the macropad with the
+/// matching profile, debouncing rapid focus changes and skipping re-flashing
+/// when the resolved profile is already active.
+pub struct ProfileWatcher {
+    backend: Box<dyn ActiveWindowBackend>,
+    base_path: PathBuf,
+    profiles: HashMap<String, PathBuf>,
+    debounce: Duration,
+    /// Profile file last flashed: `Some(None)` for the base config alone,
+    /// `None` before anything has been flashed.
+    active_profile: Option<Option<PathBuf>>,
+}
+
+impl ProfileWatcher {
+    /// `base_path` is the base `mapping.ron`; `profiles` maps a focused
+    /// app's resolved class to a profile file that partially overrides it
+    /// (see [`Mapping::read_with_profile`]). An app with no entry just gets
+    /// the base config unmodified.
+    pub fn new(
+        backend: Box<dyn ActiveWindowBackend>,
+        base_path: PathBuf,
+        profiles: HashMap<String, PathBuf>,
+        debounce: Duration,
+    ) -> Self {
+        Self {
+            backend,
+            base_path,
+            profiles,
+            debounce,
+            active_profile: None,
+        }
+    }
+
+    /// Blocks forever, polling the focused application every `debounce` and
+    /// flashing `target` whenever the resolved profile changes.
+    pub fn run(&mut self, target: &mut dyn FlashTarget) -> Result<()> {
+        loop {
+            let tick_start = Instant::now();
+            if let Err(e) = self.tick(target) {
+                warn!("profile watcher: {e}");
+            }
+            if let Some(remaining) = self.debounce.checked_sub(tick_start.elapsed()) {
+                std::thread::sleep(remaining);
+            }
+        }
+    }
+
+    /// Resolves the focused application's profile and flashes it if it
+    /// differs from what's currently active. Exposed separately from `run`
+    /// so it can be driven one step at a time (e.g. in tests).
+    ///
+    /// A backend error (e.g. a flaky `xprop` call) is treated as "nothing
+    /// changed this tick" rather than as some pseudo-profile - resolving it
+    /// to the empty string would otherwise trigger a reflash of the base
+    /// config and forget whatever profile was actually active.
+    pub fn tick(&mut self, target: &mut dyn FlashTarget) -> Result<()> {
+        let Ok(class) = self.backend.active_window_class() else {
+            return Ok(());
+        };
+
+        // apps with no registered profile all resolve to the base config
+        let profile = self.profiles.get(&class).cloned();
+        if self.active_profile.as_ref() == Some(&profile) {
+            return Ok(());
+        }
+
+        let config = self.load_profile(&class)?;
+        Mapping::validate_config(&config)?;
+        Self::flash(&config, target)?;
+
+        self.active_profile = Some(profile);
+        Ok(())
+    }
+
+    /// Loads the base config, layering `class`'s profile on top of it if one
+    /// is registered - the same base+override loader [`Mapping::read`]'s
+    /// path-configurable, profile-merging redesign introduced.
+    fn load_profile(&self, class: &str) -> Result<Macropad> {
+        let base_path = self
+            .base_path
+            .to_str()
+            .context("base profile path must be valid UTF-8")?;
+
+        match self.profiles.get(class) {
+            Some(profile_path) => {
+                let profile_path = profile_path
+                    .to_str()
+                    .context("profile path must be valid UTF-8")?;
+                Mapping::read_with_profile(base_path, profile_path)
+            }
+            None => Mapping::read_path(base_path),
+        }
+    }
+
+    fn flash(config: &Macropad, target: &mut dyn FlashTarget) -> Result<()> {
+        for (layer_idx, layer) in config.layers.iter().enumerate() {
+            let layer_idx = layer_idx as u8 + 1;
+            let mut key_pos = 0u8;
+            for row in &layer.buttons {
+                for binding in row {
+                    for msg in Messages::build_sequence_msg(binding.clone(), layer_idx, key_pos)? {
+                        target.send(&msg)?;
+                    }
+                    key_pos += 1;
+                }
+            }
+            for knob in &layer.knobs {
+                for binding in [&knob.ccw, &knob.click, &knob.cw] {
+                    for msg in Messages::build_sequence_msg(binding.clone(), layer_idx, key_pos)? {
+                        target.send(&msg)?;
+                    }
+                    key_pos += 1;
+                }
+            }
+            if let Some(led) = &layer.led {
+                target.send(&Messages::program_led_config(layer_idx, led))?;
+            }
+        }
+        target.send(&Messages::end_program())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapping::{Device, Layer};
+    use std::fs;
+
+    #[test]
+    fn parse_wm_class_extracts_general_purpose_name() -> Result<()> {
+        let xprop_output = "WM_CLASS(STRING) = \"firefox\", \"Firefox\"\n";
+        assert_eq!(parse_wm_class(xprop_output)?, "firefox");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_wm_class_errors_without_a_quoted_atom() {
+        let xprop_output = "WM_CLASS:  no such atom on any window.\n";
+        assert!(parse_wm_class(xprop_output).is_err());
+    }
+
+    #[test]
+    fn find_focused_app_id_recurses_into_floating_nodes() {
+        let tree = serde_json::json!({
+            "nodes": [{"focused": false, "app_id": "tiled"}],
+            "floating_nodes": [{"focused": true, "app_id": "Floating-Dialog"}],
+        });
+        assert_eq!(
+            find_focused_app_id(&tree),
+            Some("floating-dialog".to_string())
+        );
+    }
+
+    struct FakeBackend(String);
+
+    impl ActiveWindowBackend for FakeBackend {
+        fn active_window_class(&self) -> Result<String> {
+            Ok(self.0.clone())
+        }
+    }
+
+    struct FailingBackend;
+
+    impl ActiveWindowBackend for FailingBackend {
+        fn active_window_class(&self) -> Result<String> {
+            Err(anyhow!("xprop unavailable"))
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingTarget(Vec<Vec<u8>>);
+
+    impl FlashTarget for RecordingTarget {
+        fn send(&mut self, report: &[u8]) -> Result<()> {
+            self.0.push(report.to_vec());
+            Ok(())
+        }
+    }
+
+    fn base_config() -> Macropad {
+        Macropad {
+            device: Device {
+                orientation: "normal".to_string(),
+                rows: 1,
+                cols: 1,
+                knobs: 0,
+            },
+            layers: vec![Layer {
+                buttons: vec![vec!["a".to_string()]],
+                knobs: vec![],
+                led: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn tick_skips_reflash_when_resolved_class_is_unchanged() -> Result<()> {
+        let base_path = std::env::temp_dir().join("macropad_tool_daemon_test_skip.ron");
+        fs::write(
+            &base_path,
+            ron::ser::to_string(&base_config()).expect("serialize test config"),
+        )
+        .expect("write test config");
+
+        let mut watcher = ProfileWatcher::new(
+            Box::new(FakeBackend("firefox".to_string())),
+            base_path.clone(),
+            HashMap::new(),
+            Duration::from_millis(0),
+        );
+        let mut target = RecordingTarget::default();
+
+        watcher.tick(&mut target)?;
+        let sent_after_first_tick = target.0.len();
+        watcher.tick(&mut target)?;
+
+        fs::remove_file(&base_path).ok();
+        assert_eq!(
+            target.0.len(),
+            sent_after_first_tick,
+            "second tick for the same resolved class should not reflash"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tick_programs_led_from_profile_override() -> Result<()> {
+        use crate::keyboard::{LedColor, LedMode};
+        use crate::mapping::{LayerOverride, LedConfig, ProfileOverride};
+        use std::collections::BTreeMap;
+
+        let base_path = std::env::temp_dir().join("macropad_tool_daemon_test_led_base.ron");
+        let profile_path = std::env::temp_dir().join("macropad_tool_daemon_test_led_profile.ron");
+        fs::write(
+            &base_path,
+            ron::ser::to_string(&base_config()).expect("serialize test config"),
+        )
+        .expect("write test config");
+
+        let led = LedConfig {
+            mode: LedMode::Steady,
+            color: LedColor::Red,
+            brightness: 0,
+            speed: 0,
+        };
+        let mut layers = BTreeMap::new();
+        layers.insert(
+            0,
+            LayerOverride {
+                buttons: None,
+                knobs: BTreeMap::new(),
+                led: Some(led),
+            },
+        );
+        fs::write(
+            &profile_path,
+            ron::ser::to_string(&ProfileOverride {
+                device: None,
+                layers,
+            })
+            .expect("serialize test override"),
+        )
+        .expect("write test override");
+
+        let mut profiles = HashMap::new();
+        profiles.insert("firefox".to_string(), profile_path.clone());
+        let mut watcher = ProfileWatcher::new(
+            Box::new(FakeBackend("firefox".to_string())),
+            base_path.clone(),
+            profiles,
+            Duration::from_millis(0),
+        );
+        let mut target = RecordingTarget::default();
+        watcher.tick(&mut target)?;
+
+        fs::remove_file(&base_path).ok();
+        fs::remove_file(&profile_path).ok();
+
+        let led = LedConfig {
+            mode: LedMode::Steady,
+            color: LedColor::Red,
+            brightness: 0,
+            speed: 0,
+        };
+        let expected = Messages::program_led_config(1, &led);
+        assert!(
+            target.0.contains(&expected),
+            "expected the layer's LED config to be sent while flashing"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tick_does_nothing_on_backend_error() -> Result<()> {
+        let base_path = std::env::temp_dir().join("macropad_tool_daemon_test_backend_err.ron");
+        fs::write(
+            &base_path,
+            ron::ser::to_string(&base_config()).expect("serialize test config"),
+        )
+        .expect("write test config");
+
+        let mut watcher = ProfileWatcher::new(
+            Box::new(FailingBackend),
+            base_path.clone(),
+            HashMap::new(),
+            Duration::from_millis(0),
+        );
+        let mut target = RecordingTarget::default();
+
+        watcher.tick(&mut target)?;
+
+        fs::remove_file(&base_path).ok();
+        assert!(
+            target.0.is_empty(),
+            "a backend error should skip the tick, not reflash the base profile"
+        );
+        assert_eq!(watcher.active_profile, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::{anyhow, Error};
+use num_derive::{FromPrimitive, ToPrimitive};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use strum_macros::{Display, EnumString};
+
+/// Keyboard modifier keys, encoded as their bit position within a report's
+/// modifier byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display, FromPrimitive, ToPrimitive)]
+#[strum(ascii_case_insensitive)]
+pub enum Modifier {
+    Ctrl,
+    Shift,
+    Alt,
+    Win,
+}
+
+/// Well-known keyboard keys, encoded as their USB HID keyboard usage ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display, FromPrimitive, ToPrimitive)]
+#[strum(ascii_case_insensitive)]
+pub enum WellKnownCode {
+    A = 0x04,
+    B = 0x05,
+    C = 0x06,
+    D = 0x07,
+    E = 0x08,
+    F = 0x09,
+    G = 0x0a,
+    H = 0x0b,
+    I = 0x0c,
+    J = 0x0d,
+    K = 0x0e,
+    L = 0x0f,
+    M = 0x10,
+    N = 0x11,
+    O = 0x12,
+    P = 0x13,
+    Q = 0x14,
+    R = 0x15,
+    S = 0x16,
+    T = 0x17,
+    U = 0x18,
+    V = 0x19,
+    W = 0x1a,
+    X = 0x1b,
+    Y = 0x1c,
+    Z = 0x1d,
+    N1 = 0x1e,
+    N2 = 0x1f,
+    N3 = 0x20,
+    N4 = 0x21,
+    N5 = 0x22,
+    N6 = 0x23,
+    N7 = 0x24,
+    N8 = 0x25,
+    N9 = 0x26,
+    N0 = 0x27,
+    Enter = 0x28,
+    Esc = 0x29,
+    Backspace = 0x2a,
+    Tab = 0x2b,
+    Space = 0x2c,
+    F1 = 0x3a,
+    F2 = 0x3b,
+    F3 = 0x3c,
+    F4 = 0x3d,
+    F5 = 0x3e,
+    F6 = 0x3f,
+    F7 = 0x40,
+    F8 = 0x41,
+    F9 = 0x42,
+    F10 = 0x43,
+    F11 = 0x44,
+    F12 = 0x45,
+    Right = 0x4f,
+    Left = 0x50,
+    Down = 0x51,
+    Up = 0x52,
+}
+
+/// Consumer-control ("media") keys, encoded as their USB HID consumer usage
+/// ID. Unlike [`WellKnownCode`], these always occupy a whole chord on their
+/// own - see [`crate::mapping::Mapping::validate_key_mapping`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display, FromPrimitive, ToPrimitive)]
+#[strum(ascii_case_insensitive)]
+pub enum MediaCode {
+    VolumeUp = 0x00e9,
+    VolumeDown = 0x00ea,
+    Mute = 0x00e2,
+    PlayPause = 0x00cd,
+    NextTrack = 0x00b5,
+    PrevTrack = 0x00b6,
+    Stop = 0x00b7,
+}
+
+/// Mouse actions: clicks, wheel scrolling, and relative movement.
+///
+/// Unlike [`Modifier`]/[`WellKnownCode`]/[`MediaCode`], a mouse action's own
+/// syntax can contain a `-` (`"click-left"`) or a signed magnitude
+/// (`"mousex+10"`), so it gets a hand-written [`FromStr`] rather than
+/// `strum::EnumString`, and always occupies a whole binding on its own - see
+/// [`crate::mapping::Mapping::validate_key_mapping`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseAction {
+    ClickLeft,
+    ClickRight,
+    ClickMiddle,
+    WheelUp,
+    WheelDown,
+    MoveX(i8),
+    MoveY(i8),
+}
+
+impl FromStr for MouseAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        match lower.as_str() {
+            "click-left" => Ok(MouseAction::ClickLeft),
+            "click-right" => Ok(MouseAction::ClickRight),
+            "click-middle" => Ok(MouseAction::ClickMiddle),
+            "wheelup" => Ok(MouseAction::WheelUp),
+            "wheeldown" => Ok(MouseAction::WheelDown),
+            _ => {
+                if let Some(magnitude) = lower.strip_prefix("mousex") {
+                    Ok(MouseAction::MoveX(magnitude.parse()?))
+                } else if let Some(magnitude) = lower.strip_prefix("mousey") {
+                    Ok(MouseAction::MoveY(magnitude.parse()?))
+                } else {
+                    Err(anyhow!("unknown mouse action - {}", s))
+                }
+            }
+        }
+    }
+}
+
+/// LED colors supported by the device's lighting controller.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    EnumString,
+    Display,
+    Serialize,
+    Deserialize,
+    FromPrimitive,
+    ToPrimitive,
+)]
+#[strum(ascii_case_insensitive)]
+pub enum LedColor {
+    Off,
+    Red,
+    Green,
+    Blue,
+    Yellow,
+    Cyan,
+    Purple,
+    White,
+}
+
+/// LED animation mode, encoded into the upper nibble of the report byte that
+/// [`LedColor`] occupies the lower bits of (see `Messages::program_led`).
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    EnumString,
+    Display,
+    Serialize,
+    Deserialize,
+    FromPrimitive,
+    ToPrimitive,
+)]
+#[strum(ascii_case_insensitive)]
+pub enum LedMode {
+    Off = 0x00,
+    Steady = 0x10,
+    Breathing = 0x20,
+    Cycle = 0x30,
+    PerKey = 0x40,
+}
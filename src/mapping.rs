@@ -1,4 +1,4 @@
-use anyhow::{anyhow, Error, Result};
+use anyhow::{anyhow, Context, Error, Result};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,6 +19,20 @@ pub struct Device {
 pub struct Layer {
     pub buttons: Vec<Vec<String>>,
     pub knobs: Vec<Knob>,
+    /// Lighting for this layer. Omitted in older configs, in which case the
+    /// LEDs are left however they already were.
+    #[serde(default)]
+    pub led: Option<LedConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LedConfig {
+    pub mode: LedMode,
+    pub color: LedColor,
+    #[serde(default)]
+    pub brightness: u8,
+    #[serde(default)]
+    pub speed: u8,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,31 +42,117 @@ pub struct Knob {
     pub cw: String,
 }
 
+/// A profile file that partially overrides a base config - only the layers
+/// and knobs it mentions are replaced, everything else is left as the base
+/// defined it. Keyed by index so a profile can redefine e.g. just layer 1's
+/// knob 0 without repeating the rest of the layer.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProfileOverride {
+    #[serde(default)]
+    pub device: Option<Device>,
+    #[serde(default)]
+    pub layers: BTreeMap<usize, LayerOverride>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LayerOverride {
+    #[serde(default)]
+    pub buttons: Option<Vec<Vec<String>>>,
+    #[serde(default)]
+    pub knobs: BTreeMap<usize, Knob>,
+    #[serde(default)]
+    pub led: Option<LedConfig>,
+}
+
+impl Macropad {
+    /// Applies a profile's overrides on top of this (base) config, in place.
+    fn apply_override(&mut self, overrides: ProfileOverride) {
+        if let Some(device) = overrides.device {
+            self.device = device;
+        }
+
+        for (layer_idx, layer_override) in overrides.layers {
+            let Some(layer) = self.layers.get_mut(layer_idx) else {
+                continue;
+            };
+
+            if let Some(buttons) = layer_override.buttons {
+                layer.buttons = buttons;
+            }
+            for (knob_idx, knob) in layer_override.knobs {
+                if let Some(existing) = layer.knobs.get_mut(knob_idx) {
+                    *existing = knob;
+                }
+            }
+            if let Some(led) = layer_override.led {
+                layer.led = Some(led);
+            }
+        }
+    }
+}
+
 use ron::de::from_reader;
 use ron::ser::{to_string_pretty, PrettyConfig};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::str::FromStr;
 
 use crate::config::Orientation;
 use crate::consts;
-use crate::keyboard::{MediaCode, Modifier, WellKnownCode};
+use crate::keyboard::{LedColor, LedMode, MediaCode, Modifier, MouseAction, WellKnownCode};
+use crate::messages::Messages;
 
 pub struct Mapping {}
 
 impl Mapping {
-    pub fn read() -> Macropad {
-        // read configuration
-        let cfg_file = "./mapping.ron";
-        println!("configuration file: {}", cfg_file);
-        let f = File::open(cfg_file).expect("Failed opening file");
-        let config: Macropad = match from_reader(f) {
-            Ok(x) => x,
-            Err(e) => {
-                println!("Failed to load config: {}", e);
-                std::process::exit(1);
-            }
-        };
-        config
+    /// Builds a `Macropad` from the device's current mapping, given the raw
+    /// response `Messages::read_config` returns for each layer, in order.
+    ///
+    /// This is the read-side counterpart to the `validate` -> `build_key_msg`
+    /// -> `end_program` flashing pipeline, letting users recover what is
+    /// currently programmed, e.g. to feed into [`Mapping::print`].
+    pub fn dump(device: Device, layer_responses: &[Vec<u8>]) -> Result<Macropad> {
+        let mut layers = Vec::with_capacity(layer_responses.len());
+        for raw in layer_responses {
+            layers.push(Messages::parse_config_response(
+                raw,
+                device.rows,
+                device.cols,
+                device.knobs,
+            )?);
+        }
+        Ok(Macropad { device, layers })
+    }
+
+    /// Reads the config from `./mapping.ron`. See [`Mapping::read_path`] to
+    /// load from (or embed) an arbitrary file.
+    pub fn read() -> Result<Macropad> {
+        Self::read_path("./mapping.ron")
+    }
+
+    /// Reads the config from `path`, returning the parse error instead of
+    /// exiting, so callers (tests, the daemon, library consumers) can handle
+    /// a bad config themselves.
+    pub fn read_path(path: &str) -> Result<Macropad> {
+        println!("configuration file: {}", path);
+        let f = File::open(path).with_context(|| format!("failed opening config file {path}"))?;
+        from_reader(f).with_context(|| format!("failed parsing config file {path}"))
+    }
+
+    /// Reads the config from `path`, then layers `profile_path` on top of it
+    /// - only the layers/knobs the profile mentions are replaced, everything
+    /// else comes from the base config. Lets a profile redefine e.g. just one
+    /// layer or one knob instead of repeating the whole config.
+    pub fn read_with_profile(path: &str, profile_path: &str) -> Result<Macropad> {
+        let mut config = Self::read_path(path)?;
+
+        let f = File::open(profile_path)
+            .with_context(|| format!("failed opening profile file {profile_path}"))?;
+        let overrides: ProfileOverride = from_reader(f)
+            .with_context(|| format!("failed parsing profile file {profile_path}"))?;
+
+        config.apply_override(overrides);
+        Ok(config)
     }
 
     pub fn print(config: Macropad) {
@@ -67,9 +167,15 @@ impl Mapping {
     }
 
     pub fn validate() -> anyhow::Result<()> {
-        // check layers
-        let cfg = Self::read();
+        Self::validate_config(&Self::read()?)
+    }
 
+    /// Runs the row/column/knob and per-key checks against an already-loaded
+    /// `Macropad`, independent of where it came from. `validate` is the
+    /// `./mapping.ron` convenience wrapper around this; the daemon uses this
+    /// directly to validate whichever profile the focused application maps
+    /// to.
+    pub fn validate_config(cfg: &Macropad) -> anyhow::Result<()> {
         // check orientation
         Orientation::from_str(&Self::uppercase_first(&cfg.device.orientation))?;
 
@@ -80,7 +186,7 @@ impl Mapping {
         // check rows/cols/knobs
         for (i, layer) in cfg.layers.iter().enumerate() {
             // row check
-            if layer.buttons.len() != cfg.device.rows.into() {
+            if layer.buttons.len() != usize::from(cfg.device.rows) {
                 return Err(anyhow!(
                     "number of rows mismatch at layer {}. Expected {} rows found {}",
                     i + 1,
@@ -91,7 +197,7 @@ impl Mapping {
 
             // column check
             for (j, btn_mapping) in layer.buttons.iter().enumerate() {
-                if btn_mapping.len() != cfg.device.cols.into() {
+                if btn_mapping.len() != usize::from(cfg.device.cols) {
                     return Err(anyhow!(
                         "number of colums mismatch at layer {} row {}. Expected {} columns found {}",
                         i + 1,
@@ -109,7 +215,7 @@ impl Mapping {
             }
 
             // knob check
-            if layer.knobs.len() != cfg.device.knobs.into() {
+            if layer.knobs.len() != usize::from(cfg.device.knobs) {
                 return Err(anyhow!(
                     "number of knobs mismatch at layer {}. Expected {} knobs found {}",
                     i + 1,
@@ -117,12 +223,56 @@ impl Mapping {
                     layer.knobs.len(),
                 ));
             }
+
+            // led check
+            if let Some(led) = &layer.led {
+                if led.brightness > 100 || led.speed > 100 {
+                    return Err(anyhow!(
+                        "layer {} led brightness/speed must be between 0 and 100",
+                        i + 1
+                    ));
+                }
+            }
         }
 
         Ok(())
     }
 
     fn validate_key_mapping(key: String) -> Result<()> {
+        // a macro sequence like "ctrl-c{50}ctrl-v" is a series of
+        // independently-timed steps; validate each step on its own
+        for (_, step) in Messages::split_sequence_steps(&key)? {
+            Self::validate_chord_step(step)?;
+        }
+        Ok(())
+    }
+
+    fn validate_chord_step(key: String) -> Result<()> {
+        // a step can itself hold multiple simultaneous presses, e.g.
+        // "ctrl-a,ctrl-s" (see `Messages::build_key_msg`'s own `,`-splitting)
+        // - validate each one on its own. Mouse actions and media keys get a
+        // report of their own, so they can't share a step with other presses
+        let bindings: Vec<_> = key.split(',').collect();
+        for binding in &bindings {
+            if bindings.len() > 1
+                && (Self::is_mouse_action(binding)
+                    || Self::is_media_key(&Self::uppercase_first(binding)))
+            {
+                return Err(anyhow!("{} cannot be combined with other keys", binding));
+            }
+            Self::validate_binding(binding)?;
+        }
+        Ok(())
+    }
+
+    fn validate_binding(key: &str) -> Result<()> {
+        // mouse actions are a binding unto themselves - their own syntax can
+        // contain a '-' (e.g. "click-left"), so they must be checked against
+        // the whole string before it gets split on '-' below
+        if Self::is_mouse_action(key) {
+            return Ok(());
+        }
+
         // ensure we don't go over max
         let keys: Vec<_> = key.split('-').collect();
         if keys.len() > consts::MAX_KEY_PRESSES {
@@ -133,6 +283,7 @@ impl Mapping {
         }
 
         // check individual keys
+        let is_combo = keys.len() > 1;
         for k in keys {
             let da_key = Self::uppercase_first(k);
             println!("da_key: {da_key}");
@@ -141,13 +292,20 @@ impl Mapping {
             for i in 0..3 {
                 match i {
                     0 => {
-                        found = Self::is_control_key(&da_key);
+                        found = found || Self::is_control_key(&da_key);
                     }
                     1 => {
-                        found = Self::is_media_key(&da_key);
+                        let is_media = Self::is_media_key(&da_key);
+                        if is_media && is_combo {
+                            return Err(anyhow!(
+                                "media key - {} cannot be combined with other keys",
+                                k
+                            ));
+                        }
+                        found = found || is_media;
                     }
                     2 => {
-                        found = Self::is_regular_key(&da_key);
+                        found = found || Self::is_regular_key(&da_key);
                     }
                     _ => {
                         panic!("unaccounted key test")
@@ -199,6 +357,14 @@ impl Mapping {
         }
         false
     }
+
+    fn is_mouse_action(keystr: &str) -> bool {
+        let ma = MouseAction::from_str(keystr);
+        if ma.is_ok() {
+            return true;
+        }
+        false
+    }
 }
 
 #[cfg(test)]
@@ -207,13 +373,15 @@ mod tests {
     use crate::mapping::Mapping;
 
     #[test]
-    fn mapping_read() {
-        Mapping::read();
+    fn mapping_read() -> anyhow::Result<()> {
+        Mapping::read()?;
+        Ok(())
     }
 
     #[test]
-    fn mapping_print() {
-        Mapping::print(Mapping::read());
+    fn mapping_print() -> anyhow::Result<()> {
+        Mapping::print(Mapping::read()?);
+        Ok(())
     }
 
     #[test]
@@ -221,4 +389,69 @@ mod tests {
         Mapping::validate()?;
         Ok(())
     }
+
+    #[test]
+    fn profile_override_only_touches_what_it_mentions() {
+        use crate::mapping::{Device, Knob, Layer, LayerOverride, Macropad, ProfileOverride};
+        use std::collections::BTreeMap;
+
+        let mut config = Macropad {
+            device: Device {
+                orientation: "normal".to_string(),
+                rows: 1,
+                cols: 1,
+                knobs: 1,
+            },
+            layers: vec![Layer {
+                buttons: vec![vec!["a".to_string()]],
+                knobs: vec![Knob {
+                    ccw: "volumedown".to_string(),
+                    click: "mute".to_string(),
+                    cw: "volumeup".to_string(),
+                }],
+                led: None,
+            }],
+        };
+
+        let mut layers = BTreeMap::new();
+        layers.insert(
+            0,
+            LayerOverride {
+                buttons: None,
+                knobs: BTreeMap::from([(
+                    0,
+                    Knob {
+                        ccw: "left".to_string(),
+                        click: "volumedown".to_string(),
+                        cw: "right".to_string(),
+                    },
+                )]),
+                led: None,
+            },
+        );
+        config.apply_override(ProfileOverride {
+            device: None,
+            layers,
+        });
+
+        // the overridden knob changed...
+        assert_eq!(config.layers[0].knobs[0].ccw, "left");
+        // ...but the button the profile didn't mention is untouched
+        assert_eq!(config.layers[0].buttons[0][0], "a");
+    }
+
+    #[test]
+    fn validate_key_mapping_accepts_media_key_alone() -> anyhow::Result<()> {
+        Mapping::validate_key_mapping("volumeup".to_string())
+    }
+
+    #[test]
+    fn validate_key_mapping_accepts_sequence_with_modifiers() -> anyhow::Result<()> {
+        Mapping::validate_key_mapping("ctrl-c{50}ctrl-v".to_string())
+    }
+
+    #[test]
+    fn validate_key_mapping_accepts_simultaneous_multi_press() -> anyhow::Result<()> {
+        Mapping::validate_key_mapping("ctrl-a,ctrl-s".to_string())
+    }
 }
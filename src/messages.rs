@@ -1,12 +1,17 @@
 use crate::{
     consts,
-    keyboard::{LedColor, MediaCode, Modifier, WellKnownCode},
+    keyboard::{LedColor, LedMode, MediaCode, Modifier, MouseAction, WellKnownCode},
+    mapping::{Knob, Layer, LedConfig},
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use log::debug;
 use num::{FromPrimitive, ToPrimitive};
 use std::str::FromStr;
 
+/// Length in bytes of a single button/knob-direction binding, as produced by
+/// `build_key_msg` and friends and echoed back by the device on read.
+const BINDING_RESPONSE_LEN: usize = 65;
+
 pub struct Messages {}
 
 impl Messages {
@@ -71,16 +76,56 @@ impl Messages {
         ]
     }
 
+    /// Programs a layer's LED mode, color, brightness, and speed in one go.
+    ///
+    /// Supersedes the single global `program_led` with lighting that is
+    /// declared per layer in the RON config (`Layer::led`) and saved and
+    /// restored alongside the key mapping.
+    ///
+    /// # Arguments
+    /// `layer` - The layer this lighting applies to
+    /// `led` - The mode/color/brightness/speed to program
+    ///
+    pub fn program_led_config(layer: u8, led: &LedConfig) -> Vec<u8> {
+        let mode = <LedMode as ToPrimitive>::to_u8(&led.mode).unwrap();
+        let mut m_c = <LedColor as ToPrimitive>::to_u8(&led.color).unwrap();
+        m_c |= mode;
+        debug!("layer {layer} led mode and code:0x{:02x}", m_c);
+        vec![
+            0x03, 0xfe, 0xb0, layer, 0x08, led.brightness, led.speed, 0x00, 0x00, 0x00, 0x01,
+            0x00, m_c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ]
+    }
+
     pub fn build_key_msg(key_chord: String, layer: u8, key_pos: u8, delay: u16) -> Result<Vec<u8>> {
         let keys: Vec<_> = key_chord.split(',').collect();
+
+        // a lone mouse action (own syntax, e.g. "click-left") or media/
+        // consumer key gets its own report kind and layout;
+        // `Mapping::validate_key_mapping` rejects combining either with
+        // other keys, so this is the only form either can ever take
+        if keys.len() == 1 {
+            if let Ok(action) = MouseAction::from_str(keys[0]) {
+                return Self::build_mouse_msg(action, layer, key_pos, delay);
+            }
+            if !keys[0].contains('-') {
+                if let Ok(media) = MediaCode::from_str(keys[0]) {
+                    return Self::build_media_msg(media, layer, key_pos, delay);
+                }
+            }
+        }
+
         let mut msg = vec![
             0x03,
             0xfd,
             key_pos,
             layer,
             0x01,
-            0x00,
-            0x00,
+            (delay & 0xff) as u8,
+            (delay >> 8) as u8,
             0x00,
             0x00,
             0x00,
@@ -90,33 +135,22 @@ impl Messages {
         let mut cnt = 0;
         for binding in &keys {
             let kc: Vec<_> = binding.split('-').collect();
+            // a binding can hold any number of modifiers plus one regular
+            // key (e.g. "ctrl-shift-a"), so modifiers accumulate into a
+            // single bitmask - one bit per `Modifier`, keyed by its enum
+            // discriminant - rather than overwriting each other
             let mut m_c = 0x00u8;
             let mut wkk = 0x00;
-            for (i, key) in kc.iter().enumerate() {
-                println!("=> {key}");
-                if let Ok(m) = Modifier::from_str(&key) {
+            for key in &kc {
+                if let Ok(m) = Modifier::from_str(key) {
                     let power = <Modifier as ToPrimitive>::to_u8(&m).unwrap();
-                    m_c = 0u32.pow(power as u32) as u8;
-                    println!("11111 - {m_c}");
-                } else if let Ok(w) = WellKnownCode::from_str(&key) {
+                    m_c |= 1 << power;
+                } else if let Ok(w) = WellKnownCode::from_str(key) {
                     wkk = <WellKnownCode as ToPrimitive>::to_u8(&w).unwrap();
-                    println!("22222 - {wkk}");
-                } else if let Ok(a) = MediaCode::from_str(&key) {
-                    //m_c = <MediaCode as ToPrimitive>::to_u8(&a).unwrap();
-                    println!("33333 - FIXME: implement");
-                }
-                if kc.len() > 1 {
-                    if i == 0 {
-                        msg.extend_from_slice(&[m_c]);
-                    } else {
-                        msg.extend_from_slice(&[wkk]);
-                        cnt += 1;
-                    }
-                } else {
-                    msg.extend_from_slice(&[m_c, wkk]);
-                    cnt += 1;
                 }
             }
+            msg.extend_from_slice(&[m_c, wkk]);
+            cnt += 1;
         }
 
         for _i in 0..=(consts::MAX_KEY_PRESSES - cnt) {
@@ -131,11 +165,275 @@ impl Messages {
 
         Ok(msg)
     }
+
+    /// Builds one report per step of a macro sequence such as
+    /// `"ctrl-c{50}ctrl-v"` ("press ctrl-c, wait 50ms, press ctrl-v"),
+    /// reusing `build_key_msg` for each step with the delay that precedes it.
+    pub fn build_sequence_msg(key_chord: String, layer: u8, key_pos: u8) -> Result<Vec<Vec<u8>>> {
+        Self::split_sequence_steps(&key_chord)?
+            .into_iter()
+            .map(|(delay, step)| Self::build_key_msg(step, layer, key_pos, delay))
+            .collect()
+    }
+
+    /// Splits a chord string on its `{ms}` delay markers, returning each step
+    /// paired with the delay (in ms) to wait before it is pressed - `0` for
+    /// the first step, since nothing precedes it.
+    pub(crate) fn split_sequence_steps(key_chord: &str) -> Result<Vec<(u16, String)>> {
+        let mut steps = Vec::new();
+        let mut rest = key_chord;
+        let mut pending_delay = 0u16;
+
+        while let Some(brace_start) = rest.find('{') {
+            let (step, after) = rest.split_at(brace_start);
+            if !step.is_empty() {
+                steps.push((pending_delay, step.to_string()));
+            }
+            let brace_end = after
+                .find('}')
+                .ok_or_else(|| anyhow!("unterminated delay in chord \"{}\"", key_chord))?;
+            pending_delay = after[1..brace_end].parse()?;
+            rest = &after[brace_end + 1..];
+        }
+        if !rest.is_empty() {
+            steps.push((pending_delay, rest.to_string()));
+        }
+
+        Ok(steps)
+    }
+
+    /// Builds the report for a lone consumer/media key, e.g. `"volumeup"`.
+    ///
+    /// Report kind `0x02` marks it as a consumer usage; the usage code itself
+    /// is written little-endian into the two bytes that would otherwise hold
+    /// a `[modifier, wellknown]` pair.
+    fn build_media_msg(media: MediaCode, layer: u8, key_pos: u8, delay: u16) -> Result<Vec<u8>> {
+        let code = <MediaCode as ToPrimitive>::to_u16(&media).unwrap();
+        let mut msg = vec![
+            0x03,
+            0xfd,
+            key_pos,
+            layer,
+            0x02,
+            (delay & 0xff) as u8,
+            (delay >> 8) as u8,
+            0x00,
+            0x00,
+            0x00,
+            0x01,
+            (code & 0xff) as u8,
+            (code >> 8) as u8,
+        ];
+
+        for _i in 0..consts::MAX_KEY_PRESSES {
+            msg.extend_from_slice(&[0x00, 0x00]);
+        }
+
+        // last 18 bytes are always 0
+        msg.extend_from_slice(&[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ]);
+
+        Ok(msg)
+    }
+
+    /// Builds the report for a lone mouse action: clicks, wheel scroll, or
+    /// relative movement.
+    ///
+    /// Report kind `0x03` marks it as a mouse report; the four payload bytes
+    /// are the button bitmask, the wheel delta, and the signed X/Y deltas.
+    fn build_mouse_msg(action: MouseAction, layer: u8, key_pos: u8, delay: u16) -> Result<Vec<u8>> {
+        let (buttons, wheel, dx, dy): (u8, i8, i8, i8) = match action {
+            MouseAction::ClickLeft => (0x01, 0, 0, 0),
+            MouseAction::ClickRight => (0x02, 0, 0, 0),
+            MouseAction::ClickMiddle => (0x04, 0, 0, 0),
+            MouseAction::WheelUp => (0x00, 1, 0, 0),
+            MouseAction::WheelDown => (0x00, -1, 0, 0),
+            MouseAction::MoveX(magnitude) => (0x00, 0, magnitude, 0),
+            MouseAction::MoveY(magnitude) => (0x00, 0, 0, magnitude),
+        };
+
+        let mut msg = vec![
+            0x03,
+            0xfd,
+            key_pos,
+            layer,
+            0x03,
+            (delay & 0xff) as u8,
+            (delay >> 8) as u8,
+            0x00,
+            0x00,
+            0x00,
+            0x01,
+            buttons,
+            wheel as u8,
+            dx as u8,
+            dy as u8,
+        ];
+
+        for _i in 0..(consts::MAX_KEY_PRESSES - 1) {
+            msg.extend_from_slice(&[0x00, 0x00]);
+        }
+
+        // last 18 bytes are always 0
+        msg.extend_from_slice(&[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ]);
+
+        Ok(msg)
+    }
+
+    /// Parses a full layer's worth of button/knob-direction responses (the
+    /// inverse of `build_key_msg`/`build_media_msg`/`build_mouse_msg`) back
+    /// into a `Layer`.
+    ///
+    /// `data` is expected to hold one [`BINDING_RESPONSE_LEN`]-byte record
+    /// per button (row-major, `rows` rows of `cols` columns), followed by
+    /// one record per knob direction (`ccw`, `click`, `cw`, in that order)
+    /// for each of the `knobs` rotary encoders.
+    pub fn parse_config_response(data: &[u8], rows: u8, cols: u8, knobs: u8) -> Result<Layer> {
+        let mut records = data.chunks(BINDING_RESPONSE_LEN);
+
+        let mut buttons = Vec::with_capacity(rows as usize);
+        for _ in 0..rows {
+            let mut row = Vec::with_capacity(cols as usize);
+            for _ in 0..cols {
+                let record = records
+                    .next()
+                    .ok_or_else(|| anyhow!("config response ended before all buttons were read"))?;
+                row.push(Self::parse_binding(record)?);
+            }
+            buttons.push(row);
+        }
+
+        let mut knob_list = Vec::with_capacity(knobs as usize);
+        for _ in 0..knobs {
+            let mut next_direction = || -> Result<String> {
+                let record = records
+                    .next()
+                    .ok_or_else(|| anyhow!("config response ended before all knobs were read"))?;
+                Self::parse_binding(record)
+            };
+            knob_list.push(Knob {
+                ccw: next_direction()?,
+                click: next_direction()?,
+                cw: next_direction()?,
+            });
+        }
+
+        Ok(Layer {
+            buttons,
+            knobs: knob_list,
+            // LED state isn't part of a button/knob response - it's read
+            // back separately, if/when the device exposes that
+            led: None,
+        })
+    }
+
+    /// Parses a single button/knob-direction response back into the chord
+    /// string it was originally programmed with.
+    fn parse_binding(record: &[u8]) -> Result<String> {
+        if record.len() < 15 {
+            return Err(anyhow!("key binding response too short"));
+        }
+
+        match record[4] {
+            0x01 => {
+                let steps = record[10].max(1) as usize;
+                if record.len() < 11 + steps * 2 {
+                    return Err(anyhow!(
+                        "key binding response too short for {} step(s)",
+                        steps
+                    ));
+                }
+                let mut chord_steps = Vec::with_capacity(steps);
+                for i in 0..steps {
+                    let m_c = record[11 + i * 2];
+                    let wkk = record[12 + i * 2];
+                    // a modifier-only binding (e.g. "ctrl") leaves the key byte 0
+                    let name = if wkk == 0 {
+                        String::new()
+                    } else {
+                        let code = <WellKnownCode as FromPrimitive>::from_u8(wkk)
+                            .ok_or_else(|| anyhow!("unknown keyboard usage code 0x{:02x}", wkk))?;
+                        code.to_string().to_lowercase()
+                    };
+
+                    // m_c is a bitmask, one bit per Modifier (its enum
+                    // discriminant is its bit position), so every held
+                    // modifier round-trips instead of just Ctrl
+                    let mut prefix = String::new();
+                    for bit in 0..8u8 {
+                        if m_c & (1 << bit) == 0 {
+                            continue;
+                        }
+                        if let Some(modifier) = <Modifier as FromPrimitive>::from_u8(bit) {
+                            prefix.push_str(&modifier.to_string().to_lowercase());
+                            prefix.push('-');
+                        }
+                    }
+                    chord_steps.push(format!("{prefix}{name}").trim_end_matches('-').to_string());
+                }
+                Ok(chord_steps.join(","))
+            }
+            0x02 => {
+                let code = u16::from(record[11]) | (u16::from(record[12]) << 8);
+                let media = <MediaCode as FromPrimitive>::from_u16(code)
+                    .ok_or_else(|| anyhow!("unknown media usage code 0x{:04x}", code))?;
+                Ok(media.to_string().to_lowercase())
+            }
+            0x03 => {
+                let buttons = record[11];
+                let wheel = record[12] as i8;
+                let dx = record[13] as i8;
+                let dy = record[14] as i8;
+                if buttons & 0x01 != 0 {
+                    Ok("click-left".to_string())
+                } else if buttons & 0x02 != 0 {
+                    Ok("click-right".to_string())
+                } else if buttons & 0x04 != 0 {
+                    Ok("click-middle".to_string())
+                } else if wheel > 0 {
+                    Ok("wheelup".to_string())
+                } else if wheel < 0 {
+                    Ok("wheeldown".to_string())
+                } else if dx != 0 {
+                    Ok(format!("mousex{dx:+}"))
+                } else {
+                    Ok(format!("mousey{dy:+}"))
+                }
+            }
+            kind => Err(anyhow!("unsupported report kind 0x{:02x}", kind)),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::messages::Messages;
+    use crate::{
+        keyboard::{LedColor, LedMode, Modifier, WellKnownCode},
+        mapping::LedConfig,
+        messages::Messages,
+    };
+    use num::ToPrimitive;
+
+    #[test]
+    fn led_config() {
+        let led = LedConfig {
+            mode: LedMode::Breathing,
+            color: LedColor::Cyan,
+            brightness: 80,
+            speed: 3,
+        };
+        let msg = Messages::program_led_config(2, &led);
+        assert_eq!(msg.len(), 65);
+        assert_eq!(msg[3], 2);
+        assert_eq!(msg[5], 80);
+        assert_eq!(msg[6], 3);
+        assert_eq!(msg[12], 0x20 | 0x05); // Breathing | Cyan
+    }
 
     #[test]
     fn ctrl_a_ctrl_s() -> anyhow::Result<()> {
@@ -162,4 +460,124 @@ mod tests {
         assert_eq!(msg[12], 0x04);
         Ok(())
     }
+
+    #[test]
+    fn media_key() -> anyhow::Result<()> {
+        // volumeup -> consumer report kind, usage 0x00e9 little-endian
+        let msg = Messages::build_key_msg("volumeup".to_string(), 1u8, 1u8, 0)?;
+        println!("{:02x?}", msg);
+        assert_eq!(msg.len(), 65);
+        assert_eq!(msg[4], 0x02);
+        assert_eq!(msg[10], 0x01);
+        assert_eq!(msg[11], 0xe9);
+        assert_eq!(msg[12], 0x00);
+        Ok(())
+    }
+
+    #[test]
+    fn mouse_click() -> anyhow::Result<()> {
+        let msg = Messages::build_key_msg("click-left".to_string(), 1u8, 1u8, 0)?;
+        println!("{:02x?}", msg);
+        assert_eq!(msg.len(), 65);
+        assert_eq!(msg[4], 0x03);
+        assert_eq!(msg[11], 0x01);
+        assert_eq!(msg[12], 0x00);
+        Ok(())
+    }
+
+    #[test]
+    fn mouse_move() -> anyhow::Result<()> {
+        let msg = Messages::build_key_msg("mousex+10".to_string(), 1u8, 1u8, 0)?;
+        println!("{:02x?}", msg);
+        assert_eq!(msg.len(), 65);
+        assert_eq!(msg[4], 0x03);
+        assert_eq!(msg[13], 10);
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_keyboard_chord() -> anyhow::Result<()> {
+        let msg = Messages::build_key_msg("ctrl-a".to_string(), 1u8, 1u8, 0)?;
+        assert_eq!(Messages::parse_binding(&msg)?, "ctrl-a");
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_non_ctrl_modifier() -> anyhow::Result<()> {
+        let msg = Messages::build_key_msg("shift-a".to_string(), 1u8, 1u8, 0)?;
+        assert_eq!(Messages::parse_binding(&msg)?, "shift-a");
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_media_key() -> anyhow::Result<()> {
+        let msg = Messages::build_key_msg("volumeup".to_string(), 1u8, 1u8, 0)?;
+        assert_eq!(Messages::parse_binding(&msg)?, "volumeup");
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_mouse_action() -> anyhow::Result<()> {
+        let msg = Messages::build_key_msg("click-left".to_string(), 1u8, 1u8, 0)?;
+        assert_eq!(Messages::parse_binding(&msg)?, "click-left");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_binding_decodes_multiple_modifier_bits() -> anyhow::Result<()> {
+        // exercise the decode side directly with a hand-built record holding
+        // more than one modifier bit, to confirm every set bit round-trips
+        // rather than just the first one matched
+        let mut record = vec![0u8; 65];
+        record[4] = 0x01;
+        record[10] = 1;
+        record[11] = (1 << <Modifier as ToPrimitive>::to_u8(&Modifier::Ctrl).unwrap())
+            | (1 << <Modifier as ToPrimitive>::to_u8(&Modifier::Shift).unwrap());
+        record[12] = <WellKnownCode as ToPrimitive>::to_u8(&WellKnownCode::A).unwrap();
+        assert_eq!(Messages::parse_binding(&record)?, "ctrl-shift-a");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_binding_rejects_truncated_multi_step_record() {
+        let mut record = vec![0u8; 65];
+        record[4] = 0x01;
+        record[10] = 200; // claims far more steps than the record has room for
+        assert!(Messages::parse_binding(&record).is_err());
+    }
+
+    #[test]
+    fn parse_config_response_single_button() -> anyhow::Result<()> {
+        let msg = Messages::build_key_msg("a".to_string(), 1u8, 1u8, 0)?;
+        let layer = Messages::parse_config_response(&msg, 1, 1, 0)?;
+        assert_eq!(layer.buttons, vec![vec!["a".to_string()]]);
+        assert!(layer.knobs.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn sequence_with_delay() -> anyhow::Result<()> {
+        let msgs = Messages::build_sequence_msg("ctrl-c{50}ctrl-v".to_string(), 1u8, 1u8)?;
+        assert_eq!(msgs.len(), 2);
+
+        // first step has no delay
+        assert_eq!(msgs[0][5], 0x00);
+        assert_eq!(msgs[0][6], 0x00);
+        assert_eq!(msgs[0][12], 0x06); // c
+
+        // second step waits 50ms before pressing
+        assert_eq!(msgs[1][5], 50);
+        assert_eq!(msgs[1][6], 0x00);
+        assert_eq!(msgs[1][12], 0x19); // v
+
+        Ok(())
+    }
+
+    #[test]
+    fn sequence_without_delay_matches_single_report() -> anyhow::Result<()> {
+        let single = Messages::build_key_msg("ctrl-a".to_string(), 1u8, 1u8, 0)?;
+        let sequence = Messages::build_sequence_msg("ctrl-a".to_string(), 1u8, 1u8)?;
+        assert_eq!(sequence, vec![single]);
+        Ok(())
+    }
 }